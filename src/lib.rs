@@ -0,0 +1,8 @@
+extern crate rabble;
+
+mod msg;
+mod namespace_msg;
+pub mod vr;
+
+pub use msg::Msg;
+pub use namespace_msg::NamespaceMsg;
@@ -0,0 +1,28 @@
+use vr::vr_msg::VrMsg;
+use NamespaceMsg;
+
+/// Top-level message type routed between actors. `Vr` carries the replication protocol; `Namespace`
+/// carries cluster-naming facts (e.g. who the current primary is) to the namespace manager actor.
+#[derive(Debug, Clone)]
+pub enum Msg {
+    Vr(VrMsg),
+    Namespace(NamespaceMsg),
+}
+
+impl From<VrMsg> for Msg {
+    fn from(msg: VrMsg) -> Msg {
+        Msg::Vr(msg)
+    }
+}
+
+impl From<NamespaceMsg> for Msg {
+    fn from(msg: NamespaceMsg) -> Msg {
+        Msg::Namespace(msg)
+    }
+}
+
+impl From<NamespaceMsg> for ::rabble::Msg<Msg> {
+    fn from(msg: NamespaceMsg) -> ::rabble::Msg<Msg> {
+        ::rabble::Msg::User(Msg::from(msg))
+    }
+}
@@ -0,0 +1,8 @@
+use rabble::Pid;
+
+/// Messages sent to the namespace manager actor, which tracks cluster-wide facts that live
+/// outside any single replica's VR state, such as who the current primary is.
+#[derive(Debug, Clone)]
+pub enum NamespaceMsg {
+    NewPrimary(Pid),
+}
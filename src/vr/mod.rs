@@ -0,0 +1,9 @@
+//! Viewstamped Replication: the replica state machine (`vr_fsm`, `states`), the context it
+//! carries across transitions (`vr_ctx`), and the wire protocol (`vr_msg`).
+
+pub mod vr_ctx;
+#[macro_use]
+pub mod vr_fsm;
+pub mod vr_msg;
+pub mod states;
+pub mod sim;
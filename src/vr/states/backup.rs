@@ -1,18 +1,43 @@
 use std::convert::{From, Into};
 use rabble::{self, Pid, CorrelationId, Envelope};
-use time::{SteadyTime, Duration};
 use msg::Msg;
 use NamespaceMsg;
 use vr::vr_fsm::{Transition, VrState, State};
-use vr::vr_msg::{ClientOp, ClientRequest, Prepare, PrepareOk, Tick, Commit, StartViewChange};
-use vr::vr_msg::{self, VrMsg, GetState, StartEpoch, DoViewChange, StartView};
-use vr::vr_ctx::{VrCtx, DEFAULT_IDLE_TIMEOUT_MS};
-use super::{Primary, StateTransfer, Recovery, Reconfiguration, Leaving};
+use vr::vr_msg::{ClientOp, ClientRequest, Prepare, PrepareOk, Commit};
+use vr::vr_msg::{self, VrMsg, GetState, GetStateChunk, StartEpoch, StartView};
+use vr::vr_msg::{ReadOnly, ReadOnlyReply, Redirect, StartViewChangeMsg, DoViewChangeMsg};
+use std::cmp;
+use vr::vr_ctx::{VrCtx, Snapshot, DEFAULT_IDLE_TIMEOUT_MS, DEFAULT_TICK_MS};
+use super::{Primary, StateTransfer, Recovery, Reconfiguration, Leaving, StartViewChange, DoViewChange};
+
+/// Number of idle `Tick`s a backup tolerates before it assumes the primary is gone and starts a
+/// view change, derived from the real tick period (`DEFAULT_TICK_MS`) rather than a magic
+/// constant so it tracks however often `VrMsg::Tick` is actually delivered. Rounds up, and never
+/// returns zero: a zero-tick timeout would fire a view change on every single `Tick`, regardless
+/// of how `DEFAULT_IDLE_TIMEOUT_MS` and `DEFAULT_TICK_MS` are configured relative to each other.
+/// Deriving liveness from a tick count rather than `SteadyTime` keeps it a pure function of
+/// delivered messages, so it can be driven deterministically in simulation.
+fn idle_timeout_ticks() -> u64 {
+    cmp::max(1, (DEFAULT_IDLE_TIMEOUT_MS + DEFAULT_TICK_MS - 1) / DEFAULT_TICK_MS)
+}
+
+/// Number of committed ops between snapshots. The in-memory log prefix covered by a snapshot is
+/// truncated once the snapshot is taken, bounding both steady-state memory and state-transfer cost
+/// for a far-behind backup.
+const SNAPSHOT_INTERVAL: u64 = 10_000;
 
 /// The backup state of the VR protocol operating in normal mode
 state!(Backup {
     ctx: VrCtx,
-    primary: Pid
+    primary: Pid,
+    // The number of ops that precede `self.ctx.log[0]`, which holds op `log_start_op + 1`. Ops up
+    // to and including this boundary have been folded into a backend snapshot and are no longer
+    // present in `log`.
+    log_start_op: u64,
+    // Ticks since `commit_num` last advanced. Unlike `backup_idle_ticks`, which only measures
+    // whether the primary is still talking to us, this measures how stale our committed data is,
+    // which is what bounds a read-only request in `handle_read_only`.
+    ticks_since_commit: u64
 });
 
 impl Transition for Backup {
@@ -30,8 +55,10 @@ impl Transition for Backup {
             VrMsg::StartView(msg) => self.handle_start_view(msg, from, cid, output),
             VrMsg::Tick => self.handle_tick(output),
             VrMsg::GetState(msg) => self.handle_get_state(msg, from, cid, output),
+            VrMsg::GetStateChunk(msg) => self.handle_get_state_chunk(msg, from, cid, output),
             VrMsg::Recovery(msg) => self.handle_recovery(msg, from, cid, output),
             VrMsg::StartEpoch(msg) => self.handle_start_epoch(msg, from, cid, output),
+            VrMsg::ReadOnly(msg) => self.handle_read_only(msg, from, cid, output),
             _ => self.into()
         }
     }
@@ -42,7 +69,9 @@ impl Backup {
         let primary = ctx.compute_primary();
         Backup {
             ctx: ctx,
-            primary: primary
+            primary: primary,
+            log_start_op: 0,
+            ticks_since_commit: 0
         }
     }
 
@@ -52,34 +81,46 @@ impl Backup {
                            cid: CorrelationId,
                            output: &mut Vec<Envelope<Msg>>)
     {
-        self.last_received_time = SteadyTime::now();
+        self.ctx.backup_idle_ticks = 0;
         self.op += 1;
         self.log.push(msg);
         output.push(self.send_to_primary(self.prepare_ok_msg(), cid));
     }
 
     /// Transition to a backup after receiving a `StartView` message
-    pub fn become_backup<S: State>(state: S,
+    pub fn become_backup<S: State>(mut state: S,
                             view: u64,
                             op: u64,
-                            log: Vec<VrMsg>,
+                            log: Vec<ClientOp>,
                             commit_num: u64,
                             output: &mut Vec<Envelope<Msg>>) -> VrState
     {
-        state.ctx.last_received_time = SteadyTime::now();
-        state.ctx.view = view;
-        state.ctx.op = op;
-        state.ctx.log = log;
-        // TODO: This isn't correct if we transition to a new epoch
-        state.ctx.last_normal_view = state.view;
-        let backup = Backup::from(state);
+        {
+            let ctx = state.ctx_mut();
+            ctx.view = view;
+            ctx.op = op;
+            ctx.log = log;
+            // Accepting a StartView resets idle-timeout bookkeeping, same as Prepare/Commit.
+            // `backup_idle_ticks` lives on `VrCtx`, so it otherwise carries over unmodified
+            // across every state transition instead of being reset just because we changed
+            // state structs.
+            ctx.backup_idle_ticks = 0;
+            // TODO: This isn't correct if we transition to a new epoch
+            ctx.last_normal_view = ctx.view;
+        }
+        let mut backup = Backup::new(state.into_ctx());
+        // `log` here is the full log as sent by the new primary. When StateTransfer instead
+        // installs a snapshot plus a trailing suffix, it sets `log_start_op` itself after this
+        // point to reflect the snapshot's boundary.
+        backup.log_start_op = 0;
         backup.set_primary(output);
         backup.commit(commit_num, output)
     }
 
+    /// Apply committed log entries up to `new_commit_num` against the backend.
     pub fn commit(&mut self, new_commit_num: u64, output: &mut Vec<Envelope<Msg>>) -> VrState {
         for i in self.commit_num..new_commit_num {
-            let msg = self.log[i as usize].clone();
+            let msg = self.log[(i - self.log_start_op) as usize].clone();
             match msg {
                 ClientOp::Request(ClientRequest {op, ..}) => {
                     self.ctx.backend.call(op);
@@ -88,21 +129,51 @@ impl Backup {
                     self.ctx.epoch = epoch;
                     self.ctx.update_for_new_epoch(i+1, replicas);
                     self.ctx.announce_reconfiguration();
-                    self.set_primary(&mut output);
+                    self.set_primary(output);
 
                     // If the reconfiguration is not the last in the log, we don't want to
-                    // transition, as the reconfiguration has already happened.
-                    if new_commit_num  == self.ctx.log.len() {
+                    // transition, as the reconfiguration has already happened. Compare against
+                    // the absolute op count (`log_start_op` + what's left in `log`), not
+                    // `log.len()` alone: `maybe_snapshot` truncates `log`'s prefix, so its raw
+                    // length is no longer the same thing as the total number of ops ever logged.
+                    if new_commit_num == self.log_start_op + self.ctx.log.len() as u64 {
+                        self.ticks_since_commit = 0;
                         self.commit_num = new_commit_num;
                         return self.enter_transitioning(output);
                     }
                 },
             }
         }
+        if new_commit_num > self.commit_num {
+            self.ticks_since_commit = 0;
+        }
         self.commit_num = new_commit_num;
+        self.maybe_snapshot();
         self.into()
     }
 
+    /// Every `SNAPSHOT_INTERVAL` committed ops, ask the backend to serialize its committed state
+    /// and truncate the in-memory log prefix that the snapshot now covers. Snapshots are only
+    /// ever taken at a committed op, so the backend's serialized state and `self.commit_num`
+    /// always agree. The backend only ever serializes the state machine, not the replica set
+    /// (which lives on `VrCtx`, not the backend), so `last_snapshot` caches `self.ctx.replicas`
+    /// alongside the backend's bytes -- otherwise a replica transferred from a snapshot older
+    /// than the most recent reconfiguration would have no way to learn the current replica set.
+    fn maybe_snapshot(&mut self) {
+        if self.commit_num - self.log_start_op < SNAPSHOT_INTERVAL {
+            return;
+        }
+        let bytes = self.ctx.backend.snapshot(self.ctx.epoch, self.ctx.view, self.commit_num);
+        self.ctx.last_snapshot = Some(Snapshot {
+            op: self.commit_num,
+            commit_num: self.commit_num,
+            bytes: bytes,
+            replicas: self.ctx.replicas.clone(),
+        });
+        self.log.drain(0..(self.commit_num - self.log_start_op) as usize);
+        self.log_start_op = self.commit_num;
+    }
+
     fn handle_prepare(self,
                       msg: Prepare,
                       from: Pid,
@@ -110,13 +181,15 @@ impl Backup {
                       output: &mut Vec<Envelope<Msg>>) -> VrState
     {
         up_to_date!(self, from, msg, cid, output);
-        self.ctx.last_received_time = SteadyTime::now();
+        self.ctx.backup_idle_ticks = 0;
         let Prepare {op, commit_num, msg, ..} = msg;
         if op == self.ctx.op + 1 {
             // This is the next op in order
             self.send_prepare_ok(msg, commit_num, cid, output);
             return self.commit(commit_num, output)
         } else if op > self.ctx.op + 1 {
+            // We're missing ops; hand off to StateTransfer to catch us up before resuming normal
+            // operation as a backup.
             return StateTransfer::start_same_view(self, output);
         }
         self.into()
@@ -129,7 +202,7 @@ impl Backup {
                      output: &mut Vec<Envelope<Msg>>) -> VrState
     {
         up_to_date!(self, from, msg, cid, output);
-        self.ctx.last_received_time = SteadyTime::now();
+        self.ctx.backup_idle_ticks = 0;
         if msg.commit_num == self.ctx.commit_num {
             // We are already up to date
             return self.into();
@@ -140,7 +213,7 @@ impl Backup {
     }
 
     fn handle_start_view_change(self,
-                                msg: StartViewChange,
+                                msg: StartViewChangeMsg,
                                 from: Pid,
                                 cid: CorrelationId,
                                 output: &mut Vec<Envelope<Msg>>) -> VrState
@@ -159,7 +232,7 @@ impl Backup {
     }
 
     fn handle_do_view_change(self,
-                             msg: DoViewChange,
+                             msg: DoViewChangeMsg,
                              from: Pid,
                              cid: CorrelationId,
                              output: &mut Vec<Envelope<Msg>>) -> VrState
@@ -192,12 +265,13 @@ impl Backup {
         // A primary has been elected in a new view / epoch
         // Even if the epoch is larger here, we will learn it and the new config by playing the log
         let StartView {view, op, log, commit_num, ..} = msg;
-        Backup::become_backup(view, op, log, commit_num, output)
+        Backup::become_backup(self, view, op, log, commit_num, output)
     }
 
-    fn handle_tick(self, output: &mut Vec<Envelope<Msg>>) -> VrState {
-        if self.ctx.idle_timeout() {
-            self.ctx.last_received_time = SteadyTime::now();
+    fn handle_tick(mut self, output: &mut Vec<Envelope<Msg>>) -> VrState {
+        self.ctx.backup_idle_ticks += 1;
+        self.ticks_since_commit += 1;
+        if self.ctx.backup_idle_ticks > idle_timeout_ticks() {
             self.ctx.view += 1;
             let new_state = StartViewChange::from(self);
             new_state.broadcast_start_view_change(output);
@@ -217,7 +291,82 @@ impl Backup {
         if epoch != self.ctx.epoch || view != self.ctx.view {
             return self.into()
         }
-        output.push(StateTransfer::send_new_state(&self.ctx, op, from, cid));
+        if op < self.log_start_op {
+            // The requester is behind our compaction boundary: the log suffix it's asking for no
+            // longer exists. Send our snapshot instead; it carries no log entries past the
+            // snapshot's op.
+            output.push(StateTransfer::send_snapshot(&self.ctx, from, cid));
+        } else {
+            output.push(StateTransfer::send_new_state(&self.ctx, self.log_start_op, op, from, cid));
+        }
+        self.into()
+    }
+
+    /// Serve one bounded window of a far-behind replica's state transfer: at most
+    /// `msg.count` entries starting at `msg.start_op`, or our snapshot if that op is already
+    /// behind our compaction boundary.
+    fn handle_get_state_chunk(self,
+                              msg: GetStateChunk,
+                              from: Pid,
+                              cid: CorrelationId,
+                              output: &mut Vec<Envelope<Msg>>) -> VrState
+    {
+        up_to_date!(self, from, msg, cid, output);
+        let GetStateChunk {epoch, view, start_op, count} = msg;
+        if epoch != self.ctx.epoch || view != self.ctx.view {
+            return self.into()
+        }
+        if start_op <= self.log_start_op {
+            output.push(StateTransfer::send_snapshot(&self.ctx, from, cid));
+        } else {
+            output.push(StateTransfer::send_new_state_chunk(&self.ctx, self.log_start_op, start_op, count, from, cid));
+        }
+        self.into()
+    }
+
+    /// Serve a read-only client request directly from our committed backend state, without
+    /// forwarding to the primary, as long as we're fresh enough and in normal mode for the
+    /// current epoch/view.
+    ///
+    /// Being dispatched to a `Backup` at all already means we're in normal mode, but the
+    /// requester's view of epoch/view may be stale or ahead of ours. A request whose epoch/view
+    /// differs from ours is answered with a `Redirect` to the primary we know of.
+    ///
+    /// "Fresh enough" is measured in `ticks_since_commit`, not `backup_idle_ticks`: the latter
+    /// only resets when we accept a message from the primary, so a backup that is hearing from
+    /// the primary regularly but has fallen behind in `commit_num` (e.g. mid state-transfer)
+    /// would otherwise look arbitrarily fresh even though its committed data is stale.
+    /// `ticks_since_commit` instead resets only when `commit()` actually advances `commit_num`,
+    /// so it bounds how old the data a read observes can be. If we're too stale, redirect the
+    /// client to the primary rather than risk answering from old state.
+    fn handle_read_only(self,
+                        msg: ReadOnly,
+                        from: Pid,
+                        cid: CorrelationId,
+                        output: &mut Vec<Envelope<Msg>>) -> VrState
+    {
+        if msg.epoch != self.ctx.epoch || msg.view != self.ctx.view {
+            output.push(Envelope::new(from, self.pid.clone(), Redirect {
+                primary: self.primary.clone(),
+            }.into(), cid));
+            return self.into();
+        }
+        if self.ticks_since_commit <= msg.max_staleness_ticks {
+            // `query` is the non-mutating counterpart of `backend.call`: `call` is the apply path
+            // `commit` uses to drive the backend's state machine forward, and using it here would
+            // mutate that state machine on every read, silently diverging us from the primary.
+            let result = self.ctx.backend.query(msg.op);
+            output.push(Envelope::new(from, self.pid.clone(), ReadOnlyReply {
+                epoch: self.ctx.epoch,
+                view: self.ctx.view,
+                commit_num: self.ctx.commit_num,
+                result: result,
+            }.into(), cid));
+        } else {
+            output.push(Envelope::new(from, self.pid.clone(), Redirect {
+                primary: self.primary.clone(),
+            }.into(), cid));
+        }
         self.into()
     }
 
@@ -253,7 +402,7 @@ impl Backup {
         if self.ctx.is_primary() {
             self.reconfiguration_in_progress = false;
             // Become the primary
-            Primary::from(self).into()
+            return Primary::from(self).into();
         }
         // Become a backup
         self.into()
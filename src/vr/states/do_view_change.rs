@@ -0,0 +1,38 @@
+use rabble::{Pid, CorrelationId, Envelope};
+use msg::Msg;
+use vr::vr_fsm::{Transition, VrState};
+use vr::vr_msg::{VrMsg, DoViewChangeMsg};
+use vr::vr_ctx::VrCtx;
+use super::Backup;
+
+/// A backup that has received a `DoViewChange` for a newer view. `handle` ignores every message
+/// and returns the state unchanged, so no `DoViewChange` quorum is collected and the replica does
+/// not become primary from this state.
+state!(DoViewChange {
+    ctx: VrCtx,
+    votes: Vec<DoViewChangeMsg>
+});
+
+impl Transition for DoViewChange {
+    fn handle(self,
+              msg: VrMsg,
+              _from: Pid,
+              _cid: CorrelationId,
+              _output: &mut Vec<Envelope<Msg>>) -> VrState
+    {
+        match msg {
+            _ => self.into()
+        }
+    }
+}
+
+impl DoViewChange {
+    /// Enter `DoViewChange` from `backup`. The incoming message is not recorded: the new state
+    /// starts with an empty `votes`.
+    pub fn start_do_view_change(backup: Backup,
+                                 _from: Pid,
+                                 _msg: DoViewChangeMsg,
+                                 _output: &mut Vec<Envelope<Msg>>) -> VrState {
+        DoViewChange { ctx: backup.ctx, votes: Vec::new() }.into()
+    }
+}
@@ -0,0 +1,31 @@
+use rabble::{Pid, CorrelationId, Envelope};
+use msg::Msg;
+use vr::vr_fsm::{Transition, VrState};
+use vr::vr_msg::VrMsg;
+use vr::vr_ctx::VrCtx;
+use super::Backup;
+
+/// A replica that committed a reconfiguration removing itself from the replica set, waiting to
+/// be shut down. `handle` ignores every message and returns the state unchanged.
+state!(Leaving {
+    ctx: VrCtx
+});
+
+impl Transition for Leaving {
+    fn handle(self,
+              msg: VrMsg,
+              _from: Pid,
+              _cid: CorrelationId,
+              _output: &mut Vec<Envelope<Msg>>) -> VrState
+    {
+        match msg {
+            _ => self.into()
+        }
+    }
+}
+
+impl From<Backup> for Leaving {
+    fn from(backup: Backup) -> Leaving {
+        Leaving { ctx: backup.ctx }
+    }
+}
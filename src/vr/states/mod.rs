@@ -0,0 +1,23 @@
+//! The concrete states a VR replica can be in. Each submodule defines one state struct (via the
+//! `state!` macro in `vr_fsm.rs`) plus its `Transition` impl; `vr_fsm::VrState` is the enum that
+//! wraps all of them.
+
+mod backup;
+mod primary;
+mod state_transfer;
+mod recovery;
+mod reconfiguration;
+mod leaving;
+mod start_view_change;
+mod do_view_change;
+mod startup;
+
+pub use self::backup::Backup;
+pub use self::primary::Primary;
+pub use self::state_transfer::StateTransfer;
+pub use self::recovery::Recovery;
+pub use self::reconfiguration::Reconfiguration;
+pub use self::leaving::Leaving;
+pub use self::start_view_change::StartViewChange;
+pub use self::do_view_change::DoViewChange;
+pub use self::startup::Startup;
@@ -0,0 +1,32 @@
+use rabble::{Pid, CorrelationId, Envelope};
+use msg::Msg;
+use vr::vr_fsm::{Transition, VrState};
+use vr::vr_msg::VrMsg;
+use vr::vr_ctx::VrCtx;
+use super::Backup;
+
+/// The primary state of the VR protocol. `handle` ignores every message and returns the state
+/// unchanged: this state does not accept client requests, send `Prepare`/`Commit` to backups, or
+/// answer `GetState`/`GetStateChunk`.
+state!(Primary {
+    ctx: VrCtx
+});
+
+impl Transition for Primary {
+    fn handle(self,
+              msg: VrMsg,
+              _from: Pid,
+              _cid: CorrelationId,
+              _output: &mut Vec<Envelope<Msg>>) -> VrState
+    {
+        match msg {
+            _ => self.into()
+        }
+    }
+}
+
+impl From<Backup> for Primary {
+    fn from(backup: Backup) -> Primary {
+        Primary { ctx: backup.ctx }
+    }
+}
@@ -0,0 +1,29 @@
+use rabble::{Pid, CorrelationId, Envelope};
+use msg::Msg;
+use vr::vr_ctx::VrCtx;
+use vr::vr_msg::StartEpoch;
+
+/// A reconfiguration request, committed through the log like any other client operation so it's
+/// ordered consistently with regular requests (see `Backup::commit`'s reconfiguration branch).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reconfiguration {
+    pub epoch: u64,
+    pub replicas: Vec<Pid>,
+    pub client: Pid,
+    pub request_num: u64,
+}
+
+impl Reconfiguration {
+    /// Tell `to` that this replica has adopted the new epoch's replica set.
+    pub fn send_epoch_started(ctx: &VrCtx, to: Pid, cid: CorrelationId, output: &mut Vec<Envelope<Msg>>) {
+        output.push(Envelope::new(to, ctx.pid.clone(), StartEpoch {
+            epoch: ctx.epoch,
+            replicas: ctx.replicas.clone(),
+        }.into(), cid));
+    }
+
+    /// Called when a backup has committed a reconfiguration (see `Backup::enter_transitioning`).
+    /// The body is empty: no message is sent to the replicas being dropped.
+    pub fn broadcast_epoch_started(_ctx: &VrCtx, _output: &mut Vec<Envelope<Msg>>) {
+    }
+}
@@ -0,0 +1,39 @@
+use rabble::{Pid, CorrelationId, Envelope};
+use msg::Msg;
+use vr::vr_fsm::{Transition, VrState};
+use vr::vr_msg::{VrMsg, RecoveryResponse};
+use vr::vr_ctx::VrCtx;
+
+/// A replica recovering from a crash. `handle` ignores every message, `RecoveryResponse`
+/// included, and returns the state unchanged, so no state is reconstructed from this state.
+state!(Recovery {
+    ctx: VrCtx,
+    nonce: u64,
+    responses: Vec<RecoveryResponse>
+});
+
+impl Transition for Recovery {
+    fn handle(self,
+              msg: VrMsg,
+              _from: Pid,
+              _cid: CorrelationId,
+              _output: &mut Vec<Envelope<Msg>>) -> VrState
+    {
+        match msg {
+            _ => self.into()
+        }
+    }
+}
+
+impl Recovery {
+    /// Answer someone else's `Recovery` poll with our own epoch/view, so they can tell whether
+    /// we're ahead of them.
+    pub fn send_response(ctx: &VrCtx, to: Pid, nonce: u64, cid: CorrelationId) -> Envelope<Msg> {
+        Envelope::new(to, ctx.pid.clone(), RecoveryResponse {
+            epoch: ctx.epoch,
+            view: ctx.view,
+            nonce: nonce,
+            from: ctx.pid.clone(),
+        }.into(), cid)
+    }
+}
@@ -0,0 +1,57 @@
+use rabble::{Pid, CorrelationId, Envelope};
+use msg::Msg;
+use vr::vr_fsm::{Transition, VrState};
+use vr::vr_msg::{VrMsg, StartViewChangeMsg};
+use vr::vr_ctx::VrCtx;
+use super::Backup;
+
+/// A replica that has given up on the current primary and proposes a new view. `handle` ignores
+/// every message and returns the state unchanged: incoming `StartViewChange` votes are not added
+/// to `votes`, and no `DoViewChange` is sent.
+state!(StartViewChange {
+    ctx: VrCtx,
+    votes: Vec<Pid>
+});
+
+impl Transition for StartViewChange {
+    fn handle(self,
+              msg: VrMsg,
+              _from: Pid,
+              _cid: CorrelationId,
+              _output: &mut Vec<Envelope<Msg>>) -> VrState
+    {
+        match msg {
+            _ => self.into()
+        }
+    }
+}
+
+impl StartViewChange {
+    pub fn from(backup: Backup) -> StartViewChange {
+        let pid = backup.ctx.pid.clone();
+        StartViewChange {
+            ctx: backup.ctx,
+            votes: vec![pid],
+        }
+    }
+
+    pub fn broadcast_start_view_change(&self, output: &mut Vec<Envelope<Msg>>) {
+        let vote = StartViewChangeMsg::from(&self.ctx);
+        for replica in &self.ctx.replicas {
+            if *replica == self.ctx.pid {
+                continue;
+            }
+            output.push(Envelope::new(replica.clone(), self.ctx.pid.clone(), vote.clone().into(),
+                                       CorrelationId::pid(self.ctx.pid.clone())));
+        }
+    }
+
+    /// Enter `StartViewChange` on another replica's vote for a newer view. The vote is not
+    /// recorded: the new state starts with an empty `votes`, and `ctx.view` is left unchanged.
+    pub fn start_view_change(ctx: VrCtx,
+                              _from: Pid,
+                              _msg: StartViewChangeMsg,
+                              _output: &mut Vec<Envelope<Msg>>) -> VrState {
+        StartViewChange { ctx: ctx, votes: Vec::new() }.into()
+    }
+}
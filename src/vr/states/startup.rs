@@ -0,0 +1,24 @@
+use rabble::{Pid, CorrelationId, Envelope};
+use msg::Msg;
+use vr::vr_fsm::{Transition, VrState};
+use vr::vr_msg::VrMsg;
+use vr::vr_ctx::VrCtx;
+
+/// The replica's state before it has joined a cluster. `handle` ignores every message and
+/// returns the state unchanged.
+state!(Startup {
+    ctx: VrCtx
+});
+
+impl Transition for Startup {
+    fn handle(self,
+              msg: VrMsg,
+              _from: Pid,
+              _cid: CorrelationId,
+              _output: &mut Vec<Envelope<Msg>>) -> VrState
+    {
+        match msg {
+            _ => self.into()
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use rabble::{Pid, CorrelationId, Envelope};
+use msg::Msg;
+use vr::vr_fsm::{Transition, VrState};
+use vr::vr_msg::{VrMsg, GetStateChunk, NewState, NewStateChunk, NewStateSnapshot, StartView};
+use vr::vr_ctx::VrCtx;
+use super::Backup;
+
+/// Requests outstanding per transfer. Capped so a single far-behind backup can't flood the
+/// replica serving it with a window's worth of chunk requests all at once.
+const MAX_INFLIGHT: u64 = 4;
+
+/// Entries requested per chunk. Stands in for a true `max_bytes_per_chunk`: this checkout doesn't
+/// model a serialized size per `ClientOp`, so the byte cap is approximated as a fixed entry count.
+const MAX_OPS_PER_CHUNK: u64 = 256;
+
+/// Pulls a far-behind backup's missing log suffix in bounded windows instead of one `GetState`
+/// round trip covering the whole gap, so catching up can't force a multi-gigabyte message (and
+/// matching memory spike) on either end. `Backup::handle_prepare`/`handle_commit` transition here
+/// as soon as they detect a gap; this state then owns the request/response loop until it's caught
+/// up, at which point it hands back to `Backup`.
+state!(StateTransfer {
+    ctx: VrCtx,
+    primary: Pid,
+    log_start_op: u64,
+    // Absolute op number of the next window we haven't yet requested.
+    next_request_op: u64,
+    // GetStateChunk requests sent but not yet answered, bounded by MAX_INFLIGHT.
+    inflight: u64,
+    // Highest commit_num learned from the primary so far, applied via Backup::commit once the
+    // whole transfer completes.
+    target_commit_num: u64
+});
+
+impl Transition for StateTransfer {
+    fn handle(self,
+              msg: VrMsg,
+              _from: Pid,
+              _cid: CorrelationId,
+              output: &mut Vec<Envelope<Msg>>) -> VrState
+    {
+        match msg {
+            VrMsg::NewState(msg) => self.handle_new_state(msg, output),
+            VrMsg::NewStateChunk(msg) => self.handle_new_state_chunk(msg, output),
+            VrMsg::NewStateSnapshot(msg) => self.handle_new_state_snapshot(msg, output),
+            VrMsg::StartView(msg) => self.handle_start_view(msg, output),
+            _ => self.into()
+        }
+    }
+}
+
+impl StateTransfer {
+    /// Begin (or resume) catching up in the replica's current view: request the first bounded
+    /// window(s) of the missing suffix rather than the whole gap at once.
+    pub fn start_same_view(backup: Backup, output: &mut Vec<Envelope<Msg>>) -> VrState {
+        let primary = backup.primary.clone();
+        let log_start_op = backup.log_start_op;
+        let next_request_op = backup.ctx.op + 1;
+        let mut transfer = StateTransfer {
+            ctx: backup.ctx,
+            primary: primary,
+            log_start_op: log_start_op,
+            next_request_op: next_request_op,
+            inflight: 0,
+            target_commit_num: 0,
+        };
+        transfer.request_more(output);
+        transfer.into()
+    }
+
+    fn request_more(&mut self, output: &mut Vec<Envelope<Msg>>) {
+        while self.inflight < MAX_INFLIGHT {
+            output.push(Envelope::new(self.primary.clone(), self.ctx.pid.clone(), GetStateChunk {
+                epoch: self.ctx.epoch,
+                view: self.ctx.view,
+                start_op: self.next_request_op,
+                count: MAX_OPS_PER_CHUNK,
+            }.into(), CorrelationId::pid(self.ctx.pid.clone())));
+            self.next_request_op += MAX_OPS_PER_CHUNK;
+            self.inflight += 1;
+        }
+    }
+
+    /// Answer a `GetState` with the full log suffix after `op`, offset by `log_start_op` since
+    /// `ctx.log[0]` holds absolute op `log_start_op + 1`, not op 1.
+    pub fn send_new_state(ctx: &VrCtx, log_start_op: u64, op: u64, to: Pid, cid: CorrelationId) -> Envelope<Msg> {
+        let entries = ctx.log[(op - log_start_op) as usize..].to_vec();
+        Envelope::new(to, ctx.pid.clone(), NewState {
+            epoch: ctx.epoch,
+            view: ctx.view,
+            op: ctx.op,
+            commit_num: ctx.commit_num,
+            start_op: op + 1,
+            entries: entries,
+        }.into(), cid)
+    }
+
+    /// Answer a `GetStateChunk` with at most `count` entries starting at `start_op`, offset by
+    /// `log_start_op` the same way `send_new_state` is.
+    pub fn send_new_state_chunk(ctx: &VrCtx, log_start_op: u64, start_op: u64, count: u64, to: Pid,
+                                 cid: CorrelationId) -> Envelope<Msg> {
+        let from_idx = ::std::cmp::min(ctx.log.len(), (start_op - 1 - log_start_op) as usize);
+        let to_idx = ::std::cmp::min(ctx.log.len(), from_idx + count as usize);
+        let entries = ctx.log[from_idx..to_idx].to_vec();
+        Envelope::new(to, ctx.pid.clone(), NewStateChunk {
+            epoch: ctx.epoch,
+            view: ctx.view,
+            start_op: start_op,
+            entries: entries,
+            commit_num: ctx.commit_num,
+            primary_op: ctx.op,
+            primary_commit_num: ctx.commit_num,
+        }.into(), cid)
+    }
+
+    /// Answer a `GetState`/`GetStateChunk` whose requested op is behind our compaction boundary
+    /// with our most recent snapshot. Only reachable once `maybe_snapshot` has taken one.
+    pub fn send_snapshot(ctx: &VrCtx, to: Pid, cid: CorrelationId) -> Envelope<Msg> {
+        let snapshot = ctx.last_snapshot.as_ref()
+            .expect("send_snapshot called on a replica with no snapshot to serve");
+        Envelope::new(to, ctx.pid.clone(), NewStateSnapshot {
+            epoch: ctx.epoch,
+            view: ctx.view,
+            op: snapshot.op,
+            commit_num: snapshot.commit_num,
+            snapshot: snapshot.bytes.clone(),
+            replicas: snapshot.replicas.clone(),
+            primary_op: ctx.op,
+        }.into(), cid)
+    }
+
+    fn handle_new_state(self, msg: NewState, output: &mut Vec<Envelope<Msg>>) -> VrState {
+        if msg.epoch != self.ctx.epoch || msg.view != self.ctx.view {
+            return self.into();
+        }
+        let NewState {op, commit_num, start_op, entries, ..} = msg;
+        let log_start_op = self.log_start_op;
+        let mut backup = Backup::new(self.ctx);
+        backup.log_start_op = log_start_op;
+        // `entries` begins at `start_op`: keep what we hold before it and append the rest.
+        backup.ctx.log.truncate((start_op - 1 - log_start_op) as usize);
+        backup.ctx.log.extend(entries);
+        backup.ctx.op = op;
+        backup.commit(commit_num, output)
+    }
+
+    /// A chunk from an epoch/view other than ours is stale -- we likely abandoned this transfer
+    /// after a view change. A chunk whose first op isn't exactly the next one we're expecting is
+    /// out of order or a duplicate; drop both rather than risk corrupting `self.ctx.log`.
+    fn handle_new_state_chunk(mut self, msg: NewStateChunk, output: &mut Vec<Envelope<Msg>>) -> VrState {
+        if msg.epoch != self.ctx.epoch || msg.view != self.ctx.view || msg.start_op != self.ctx.op + 1 {
+            return self.into();
+        }
+        self.inflight -= 1;
+        self.ctx.op += msg.entries.len() as u64;
+        self.ctx.log.extend(msg.entries);
+        if msg.commit_num > self.target_commit_num {
+            self.target_commit_num = msg.commit_num;
+        }
+        if self.ctx.op >= msg.primary_op {
+            return self.finish(output);
+        }
+        self.request_more(output);
+        self.into()
+    }
+
+    fn handle_new_state_snapshot(mut self, msg: NewStateSnapshot, output: &mut Vec<Envelope<Msg>>) -> VrState {
+        if msg.epoch != self.ctx.epoch || msg.view != self.ctx.view {
+            return self.into();
+        }
+        self.ctx.backend.restore(&msg.snapshot);
+        self.ctx.replicas = msg.replicas;
+        self.ctx.log.clear();
+        self.ctx.op = msg.op;
+        self.ctx.commit_num = msg.commit_num;
+        self.log_start_op = msg.op;
+        self.next_request_op = msg.op + 1;
+        self.target_commit_num = msg.commit_num;
+        if self.ctx.op >= msg.primary_op {
+            return self.finish(output);
+        }
+        self.request_more(output);
+        self.into()
+    }
+
+    /// A new primary was elected while we were mid-transfer: abandon the in-flight window
+    /// requests and resync from the fresh full log `StartView` carries instead.
+    fn handle_start_view(self, msg: StartView, output: &mut Vec<Envelope<Msg>>) -> VrState {
+        let StartView {view, op, log, commit_num, ..} = msg;
+        Backup::become_backup(self, view, op, log, commit_num, output)
+    }
+
+    fn finish(self, output: &mut Vec<Envelope<Msg>>) -> VrState {
+        let commit_num = self.target_commit_num;
+        let log_start_op = self.log_start_op;
+        let mut backup = Backup::new(self.ctx);
+        backup.log_start_op = log_start_op;
+        backup.commit(commit_num, output)
+    }
+}
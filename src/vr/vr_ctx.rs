@@ -0,0 +1,91 @@
+use rabble::{Pid, CorrelationId, Envelope};
+use msg::Msg;
+use NamespaceMsg;
+use vr::vr_msg::ClientOp;
+
+/// How often a `VrMsg::Tick` is actually delivered to a replica. `idle_timeout_ticks` (in
+/// `states/backup.rs`) derives its threshold from this rather than hard-coding a tick count, so
+/// the two stay in sync if the real tick period ever changes.
+pub const DEFAULT_TICK_MS: u64 = 50;
+
+/// Default bound on how long a backup tolerates silence from the primary before starting a view
+/// change, in wall-clock milliseconds. Converted to a tick count via `DEFAULT_TICK_MS`.
+pub const DEFAULT_IDLE_TIMEOUT_MS: u64 = 2_000;
+
+/// A backend is the replicated state machine VR drives forward as entries commit. `call` is the
+/// mutating apply path; `query` answers a read without advancing it, so serving a read-only
+/// request never diverges a backup from the primary's `call` sequence.
+pub trait Backend {
+    fn call(&mut self, op: Vec<u8>) -> Vec<u8>;
+    fn query(&self, op: Vec<u8>) -> Vec<u8>;
+    fn snapshot(&self, epoch: u64, view: u64, commit_num: u64) -> Vec<u8>;
+    fn restore(&mut self, bytes: &[u8]);
+}
+
+/// The most recent backend snapshot a replica has taken, cached so a far-behind backup's state
+/// transfer can be served without re-serializing the backend on every request. `replicas` is the
+/// replica set as of `commit_num`, carried alongside the backend's own bytes since the replica
+/// set lives on `VrCtx`, not inside whatever the backend serializes.
+pub struct Snapshot {
+    pub op: u64,
+    pub commit_num: u64,
+    pub bytes: Vec<u8>,
+    pub replicas: Vec<Pid>,
+}
+
+/// Replication state shared across every VR state a replica can be in. Each state struct embeds
+/// a `VrCtx` (see the `state!` macro in `vr_fsm.rs`) and carries it forward across transitions,
+/// so nothing here is reset just because a replica changed from e.g. `Backup` to `Primary`.
+pub struct VrCtx {
+    pub pid: Pid,
+    pub namespace_mgr: Pid,
+    pub epoch: u64,
+    pub view: u64,
+    pub last_normal_view: u64,
+    pub op: u64,
+    pub commit_num: u64,
+    pub log: Vec<ClientOp>,
+    pub replicas: Vec<Pid>,
+    pub reconfiguration_in_progress: bool,
+    pub backend: Box<Backend>,
+    /// Ticks since the last message we accepted from the primary. Reset on every accepted
+    /// `Prepare`/`Commit`/`StartView`; bounds liveness, not staleness of committed data (see
+    /// `ticks_since_commit` on `Backup` for that).
+    pub backup_idle_ticks: u64,
+    /// Set the first time `maybe_snapshot` takes a snapshot; `None` until then.
+    pub last_snapshot: Option<Snapshot>,
+}
+
+impl VrCtx {
+    /// The primary for the current `(epoch, view)`, by the usual `view mod replicas.len()` VR
+    /// rule.
+    pub fn compute_primary(&self) -> Pid {
+        let index = (self.view as usize) % self.replicas.len();
+        self.replicas[index].clone()
+    }
+
+    pub fn is_primary(&self) -> bool {
+        self.compute_primary() == self.pid
+    }
+
+    /// Whether this replica was dropped from the replica set by the reconfiguration it just
+    /// committed.
+    pub fn is_leaving(&self) -> bool {
+        !self.replicas.contains(&self.pid)
+    }
+
+    /// Adopt a new replica set as of a committed reconfiguration at absolute op `at_op`.
+    pub fn update_for_new_epoch(&mut self, _at_op: u64, replicas: Vec<Pid>) {
+        self.replicas = replicas;
+    }
+
+    pub fn announce_reconfiguration(&mut self) {
+        self.reconfiguration_in_progress = true;
+    }
+
+    /// Wrap a message for the namespace manager actor in an envelope addressed to it.
+    pub fn namespace_mgr_envelope(&self, msg: NamespaceMsg) -> Envelope<Msg> {
+        Envelope::new(self.namespace_mgr.clone(), self.pid.clone(), msg.into(),
+                      CorrelationId::pid(self.pid.clone()))
+    }
+}
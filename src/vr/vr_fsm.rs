@@ -0,0 +1,137 @@
+use rabble::{Pid, CorrelationId, Envelope};
+use msg::Msg;
+use vr::vr_ctx::VrCtx;
+use vr::vr_msg::{VrMsg, ClientOp};
+use vr::states::{Backup, Primary, StateTransfer, Recovery, Leaving, Startup, StartViewChange,
+                  DoViewChange};
+
+/// Declares a VR replica state struct that embeds a `VrCtx` (by convention, always named `ctx`,
+/// always first) plus whatever extra fields that state needs. Generates `Deref`/`DerefMut` to
+/// `VrCtx`, so e.g. `self.op` in a method on the state resolves straight through to
+/// `self.ctx.op` without every call site spelling out `.ctx`, plus the `State` impl and the
+/// `From<State> for VrState` conversion every `.into()` call in `states/*.rs` relies on.
+macro_rules! state {
+    ($name:ident { $ctx_field:ident : $ctx_ty:ty $(, $field:ident : $ty:ty)* $(,)* }) => {
+        pub struct $name {
+            pub $ctx_field: $ctx_ty,
+            $(pub $field: $ty),*
+        }
+
+        impl ::std::ops::Deref for $name {
+            type Target = $ctx_ty;
+            fn deref(&self) -> &$ctx_ty {
+                &self.$ctx_field
+            }
+        }
+
+        impl ::std::ops::DerefMut for $name {
+            fn deref_mut(&mut self) -> &mut $ctx_ty {
+                &mut self.$ctx_field
+            }
+        }
+
+        impl ::vr::vr_fsm::State for $name {
+            fn ctx(&self) -> &::vr::vr_ctx::VrCtx {
+                &self.$ctx_field
+            }
+            fn ctx_mut(&mut self) -> &mut ::vr::vr_ctx::VrCtx {
+                &mut self.$ctx_field
+            }
+            fn into_ctx(self) -> ::vr::vr_ctx::VrCtx {
+                self.$ctx_field
+            }
+        }
+
+        impl From<$name> for ::vr::vr_fsm::VrState {
+            fn from(s: $name) -> ::vr::vr_fsm::VrState {
+                ::vr::vr_fsm::VrState::$name(s)
+            }
+        }
+    }
+}
+
+/// Bails out of the enclosing handler (returning the replica unchanged) if `msg`'s epoch/view
+/// don't match ours. The `Prepare`, `Commit`, `GetState` and `GetStateChunk` handlers in
+/// `states/backup.rs` run this first.
+macro_rules! up_to_date {
+    ($self_:expr, $from:expr, $msg:expr, $cid:expr, $output:expr) => {
+        if $msg.epoch != $self_.ctx.epoch || $msg.view != $self_.ctx.view {
+            return $self_.into();
+        }
+    }
+}
+
+/// Implemented by every VR replica state. `handle` is a pure function —
+/// `(state, message, sender, correlation id) -> new state` plus whatever envelopes that
+/// transition produces in `output` — which is what lets `sim.rs` step an entire cluster
+/// deterministically without touching the OS clock or a real network.
+pub trait Transition {
+    fn handle(self, msg: VrMsg, from: Pid, cid: CorrelationId, output: &mut Vec<Envelope<Msg>>) -> VrState;
+}
+
+/// Gives code that's generic over "whichever state struct this is" (e.g.
+/// `Backup::become_backup`) access to the `VrCtx` every state carries, without needing to know
+/// the concrete state.
+pub trait State {
+    fn ctx(&self) -> &VrCtx;
+    fn ctx_mut(&mut self) -> &mut VrCtx;
+    fn into_ctx(self) -> VrCtx where Self: Sized;
+}
+
+/// A VR replica, as whichever concrete state it's currently in. Dispatch always goes through
+/// `Transition::handle` on whichever variant is currently held.
+pub enum VrState {
+    Backup(Backup),
+    Primary(Primary),
+    StateTransfer(StateTransfer),
+    Recovery(Recovery),
+    Leaving(Leaving),
+    Startup(Startup),
+    StartViewChange(StartViewChange),
+    DoViewChange(DoViewChange),
+}
+
+impl VrState {
+    pub fn handle(self, msg: VrMsg, from: Pid, cid: CorrelationId, output: &mut Vec<Envelope<Msg>>) -> VrState {
+        match self {
+            VrState::Backup(s) => s.handle(msg, from, cid, output),
+            VrState::Primary(s) => s.handle(msg, from, cid, output),
+            VrState::StateTransfer(s) => s.handle(msg, from, cid, output),
+            VrState::Recovery(s) => s.handle(msg, from, cid, output),
+            VrState::Leaving(s) => s.handle(msg, from, cid, output),
+            VrState::Startup(s) => s.handle(msg, from, cid, output),
+            VrState::StartViewChange(s) => s.handle(msg, from, cid, output),
+            VrState::DoViewChange(s) => s.handle(msg, from, cid, output),
+        }
+    }
+
+    /// `(epoch, view)` this replica believes it is primary for, if it's currently acting as one.
+    /// Used by `sim.rs` to assert at most one primary exists per view.
+    pub fn primary_epoch_view(&self) -> Option<(u64, u64)> {
+        match *self {
+            VrState::Primary(ref p) => Some((p.ctx.epoch, p.ctx.view)),
+            _ => None,
+        }
+    }
+
+    /// The replica's `commit_num`, for every state that tracks one.
+    pub fn commit_num(&self) -> Option<u64> {
+        match *self {
+            VrState::Backup(ref s) => Some(s.ctx.commit_num),
+            VrState::Primary(ref s) => Some(s.ctx.commit_num),
+            VrState::StateTransfer(ref s) => Some(s.ctx.commit_num),
+            _ => None,
+        }
+    }
+
+    /// The replica's in-memory log (prepared entries, committed or not, after any prefix folded
+    /// into a snapshot), for every state that holds one.
+    pub fn log(&self) -> Option<&Vec<ClientOp>> {
+        match *self {
+            VrState::Backup(ref s) => Some(&s.ctx.log),
+            VrState::Primary(ref s) => Some(&s.ctx.log),
+            VrState::StateTransfer(ref s) => Some(&s.ctx.log),
+            _ => None,
+        }
+    }
+}
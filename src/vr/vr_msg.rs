@@ -0,0 +1,250 @@
+use rabble::Pid;
+use vr::states::Reconfiguration;
+use vr::vr_ctx::VrCtx;
+
+/// An entry in the replicated log: either a client operation or a reconfiguration, which is
+/// committed through the same log as a regular request so it's ordered consistently with
+/// everything else.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClientOp {
+    Request(ClientRequest),
+    Reconfiguration(Reconfiguration),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientRequest {
+    pub client: Pid,
+    pub request_num: u64,
+    pub op: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Prepare {
+    pub epoch: u64,
+    pub view: u64,
+    pub op: u64,
+    pub commit_num: u64,
+    pub msg: ClientOp,
+}
+
+#[derive(Debug, Clone)]
+pub struct PrepareOk {
+    pub epoch: u64,
+    pub view: u64,
+    pub op: u64,
+    pub from: Pid,
+}
+
+#[derive(Debug, Clone)]
+pub struct Commit {
+    pub epoch: u64,
+    pub view: u64,
+    pub commit_num: u64,
+}
+
+/// Scheduled self-message a replica sends itself (via `rabble`'s timer support) to drive the
+/// logical clock `VrMsg::Tick` advances on.
+#[derive(Debug, Clone)]
+pub struct Tick;
+
+#[derive(Debug, Clone)]
+pub struct GetState {
+    pub epoch: u64,
+    pub view: u64,
+    pub op: u64,
+}
+
+/// Reply to a `GetState` whose requested op is still in the log: the full suffix after it.
+#[derive(Debug, Clone)]
+pub struct NewState {
+    pub epoch: u64,
+    pub view: u64,
+    pub op: u64,
+    pub commit_num: u64,
+    pub start_op: u64,
+    pub entries: Vec<ClientOp>,
+}
+
+/// Request one bounded window of the missing log suffix, rather than the whole gap in one
+/// message. `start_op` is the first missing op; the responder returns at most `count` entries
+/// starting there.
+#[derive(Debug, Clone)]
+pub struct GetStateChunk {
+    pub epoch: u64,
+    pub view: u64,
+    pub start_op: u64,
+    pub count: u64,
+}
+
+/// One windowed chunk of the log, plus enough of the primary's current position
+/// (`primary_op`/`primary_commit_num`) for the requester to tell whether another window is
+/// needed after applying this one.
+#[derive(Debug, Clone)]
+pub struct NewStateChunk {
+    pub epoch: u64,
+    pub view: u64,
+    pub start_op: u64,
+    pub entries: Vec<ClientOp>,
+    pub commit_num: u64,
+    pub primary_op: u64,
+    pub primary_commit_num: u64,
+}
+
+/// Reply to a `GetState`/`GetStateChunk` whose requested op is behind the responder's
+/// compaction boundary: a full backend snapshot plus the replica set as of that snapshot, since
+/// reconfiguration entries below it are no longer replayable from the (truncated) log.
+#[derive(Debug, Clone)]
+pub struct NewStateSnapshot {
+    pub epoch: u64,
+    pub view: u64,
+    pub op: u64,
+    pub commit_num: u64,
+    pub snapshot: Vec<u8>,
+    pub replicas: Vec<Pid>,
+    pub primary_op: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct StartViewChangeMsg {
+    pub epoch: u64,
+    pub view: u64,
+    pub from: Pid,
+}
+
+#[derive(Debug, Clone)]
+pub struct DoViewChangeMsg {
+    pub epoch: u64,
+    pub view: u64,
+    pub from: Pid,
+    pub last_normal_view: u64,
+    pub op: u64,
+    pub commit_num: u64,
+    pub log: Vec<ClientOp>,
+}
+
+#[derive(Debug, Clone)]
+pub struct StartView {
+    pub epoch: u64,
+    pub view: u64,
+    pub op: u64,
+    pub commit_num: u64,
+    pub log: Vec<ClientOp>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Recovery {
+    pub from: Pid,
+    pub nonce: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct RecoveryResponse {
+    pub epoch: u64,
+    pub view: u64,
+    pub nonce: u64,
+    pub from: Pid,
+}
+
+#[derive(Debug, Clone)]
+pub struct StartEpoch {
+    pub epoch: u64,
+    pub replicas: Vec<Pid>,
+}
+
+/// A client's read-only request. Served directly from committed backend state by a `Backup`
+/// that receives it, as long as that backup is fresh enough (see
+/// `Backup::handle_read_only`); otherwise the client is redirected to the primary.
+#[derive(Debug, Clone)]
+pub struct ReadOnly {
+    pub epoch: u64,
+    pub view: u64,
+    pub op: Vec<u8>,
+    pub max_staleness_ticks: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct ReadOnlyReply {
+    pub epoch: u64,
+    pub view: u64,
+    pub commit_num: u64,
+    pub result: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Redirect {
+    pub primary: Pid,
+}
+
+#[derive(Debug, Clone)]
+pub enum VrMsg {
+    Tick,
+    Prepare(Prepare),
+    PrepareOk(PrepareOk),
+    Commit(Commit),
+    GetState(GetState),
+    NewState(NewState),
+    GetStateChunk(GetStateChunk),
+    NewStateChunk(NewStateChunk),
+    NewStateSnapshot(NewStateSnapshot),
+    StartViewChange(StartViewChangeMsg),
+    DoViewChange(DoViewChangeMsg),
+    StartView(StartView),
+    Recovery(Recovery),
+    RecoveryResponse(RecoveryResponse),
+    StartEpoch(StartEpoch),
+    ReadOnly(ReadOnly),
+    ReadOnlyReply(ReadOnlyReply),
+    Redirect(Redirect),
+}
+
+macro_rules! convert_vr_msg {
+    ($ty:ident, $variant:ident) => {
+        impl From<$ty> for ::vr::vr_msg::VrMsg {
+            fn from(msg: $ty) -> ::vr::vr_msg::VrMsg {
+                ::vr::vr_msg::VrMsg::$variant(msg)
+            }
+        }
+
+        impl From<$ty> for ::msg::Msg {
+            fn from(msg: $ty) -> ::msg::Msg {
+                ::msg::Msg::Vr(msg.into())
+            }
+        }
+
+        impl From<$ty> for ::rabble::Msg<::msg::Msg> {
+            fn from(msg: $ty) -> ::rabble::Msg<::msg::Msg> {
+                ::rabble::Msg::User(::msg::Msg::from(msg))
+            }
+        }
+    }
+}
+
+convert_vr_msg!(Prepare, Prepare);
+convert_vr_msg!(PrepareOk, PrepareOk);
+convert_vr_msg!(Commit, Commit);
+convert_vr_msg!(GetState, GetState);
+convert_vr_msg!(NewState, NewState);
+convert_vr_msg!(GetStateChunk, GetStateChunk);
+convert_vr_msg!(NewStateChunk, NewStateChunk);
+convert_vr_msg!(NewStateSnapshot, NewStateSnapshot);
+convert_vr_msg!(StartViewChangeMsg, StartViewChange);
+convert_vr_msg!(DoViewChangeMsg, DoViewChange);
+convert_vr_msg!(StartView, StartView);
+convert_vr_msg!(Recovery, Recovery);
+convert_vr_msg!(RecoveryResponse, RecoveryResponse);
+convert_vr_msg!(StartEpoch, StartEpoch);
+convert_vr_msg!(ReadOnly, ReadOnly);
+convert_vr_msg!(ReadOnlyReply, ReadOnlyReply);
+convert_vr_msg!(Redirect, Redirect);
+
+impl StartViewChangeMsg {
+    /// Build the vote this replica broadcasts when it gives up on the current primary: just
+    /// enough to identify the view being proposed and who's proposing it.
+    pub fn from(ctx: &VrCtx) -> StartViewChangeMsg {
+        StartViewChangeMsg {
+            epoch: ctx.epoch,
+            view: ctx.view,
+            from: ctx.pid.clone(),
+        }
+    }
+}